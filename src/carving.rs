@@ -1,13 +1,45 @@
 use lodepng::RGB;
-use std::i32;
+use std::i64;
 
 // as indicated by the spec, this is the energy of a complete standout pixel, and is also used for pixels on the edge.
 pub const MAX_PIXEL_ENERGY: i32 = 255 * 255 * 3;
 
+// kept small so flat (near-zero variance) regions don't cause a divide-by-zero blowup.
+const ACTIVITY_C1: f64 = 75.0;
+const ACTIVITY_C2: f64 = 5.0;
+const ACTIVITY_WINDOW: usize = 8;
+
+/// Picks the formula `Carver::calculate_energy` uses to turn pixels into a per-pixel energy map.
+#[derive(Clone, Copy, PartialEq)]
+pub enum EnergyKind {
+    DualGradient,
+    Perceptual,
+}
+
+// protecting adds a full MAX_PIXEL_ENERGY on top of the computed energy; removing overrides it
+// with a steep negative bias so the pixel is always on the cheapest path.
+const MASK_REMOVE_BIAS: i32 = -(MAX_PIXEL_ENERGY * 100);
+
+enum MaskMarker {
+    Protect,
+    Remove,
+}
+
+// green pixels protect, red pixels mark for removal; anything else (including black/white/grey) is left unbiased.
+fn mask_marker(pixel: RGB<u8>) -> Option<MaskMarker> {
+    if pixel.g > pixel.r && pixel.g > pixel.b {
+        Some(MaskMarker::Protect)
+    } else if pixel.r > pixel.g && pixel.r > pixel.b {
+        Some(MaskMarker::Remove)
+    } else {
+        None
+    }
+}
+
 /// To avoid repeated allocations, 1 carver can be created and reused indefinitely for the same image.
 pub struct Carver {
     pub energy: Vec<i32>, // energy of each pixel
-    dist_to: Vec<i32>, // should be ok recording distances as i32 as long as path is less than 20,000 pixels long
+    dist_to: Vec<i64>, // i64 because MASK_REMOVE_BIAS is large enough that an i32 path cost can overflow after only ~110 rows of removal mask
     prev_vertex: Vec<usize>, // records the path back in terms of vertices rather than edges (edge_to)
 }
 
@@ -21,21 +53,35 @@ impl Carver {
         let vertex_count = num_pixels + 2;
         Carver {
             energy: vec![0; num_pixels],
-            dist_to: vec![i32::max_value(); vertex_count],
+            dist_to: vec![i64::max_value(); vertex_count],
             prev_vertex: vec![0; vertex_count],
         }
     }
 
+    /// Grows the carver's internal buffers to fit `num_pixels`, if it's not already big enough.
+    pub fn ensure_capacity(&mut self, num_pixels: usize) {
+        if num_pixels > self.energy.len() {
+            self.energy.resize(num_pixels, 0);
+            let vertex_count = num_pixels + 2;
+            self.dist_to.resize(vertex_count, i64::max_value());
+            self.prev_vertex.resize(vertex_count, 0);
+        }
+    }
+
     fn assert_capacity_matches_image_dimensions(&self, width: usize, height: usize) {
         assert!(width * height <= self.energy.len(), "carver must have been initialised with enough size for given pixels");
     }
 
     #[inline(never)] // makes it easier to interpret callgrind output
-    pub fn calculate_energy(&mut self, width: usize, height: usize, pixels: &[RGB<u8>]) {
+    pub fn calculate_energy(&mut self, width: usize, height: usize, pixels: &[RGB<u8>], energy_kind: EnergyKind,
+                             mask: Option<&[RGB<u8>]>) {
         let num_pixels = width * height;
         self.energy.truncate(num_pixels);
         self.assert_capacity_matches_image_dimensions(width, height);
         assert!(num_pixels <= pixels.len(), "width * height must be <= given pixel slice");
+        if let Some(mask) = mask {
+            assert!(num_pixels <= mask.len(), "width * height must be <= given mask pixel slice");
+        }
 
         unsafe {
         // first row
@@ -66,7 +112,12 @@ impl Carver {
                     (y1.r as i32 - y2.r as i32).pow(2) + (y1.g as i32 - y2.g as i32).pow(2) + (y1.b as i32 - y2.b as i32).pow(2)
                 };
 
-                *self.energy.get_unchecked_mut(i) = energy_x + energy_y;
+                let gradient_energy = energy_x + energy_y;
+
+                *self.energy.get_unchecked_mut(i) = match energy_kind {
+                    EnergyKind::DualGradient => gradient_energy,
+                    EnergyKind::Perceptual => activity_mask(gradient_energy, x, y, width, height, pixels),
+                };
             }
 
             // last column
@@ -78,6 +129,18 @@ impl Carver {
             *self.energy.get_unchecked_mut(x) = MAX_PIXEL_ENERGY;
         }
         } // end unsafe
+
+        // apply the protect/remove mask, if any, after the base energy function has run so it
+        // always has the final say over whether a pixel gets carved
+        if let Some(mask) = mask {
+            for i in 0..num_pixels {
+                match mask_marker(mask[i]) {
+                    Some(MaskMarker::Protect) => self.energy[i] = self.energy[i].saturating_add(MAX_PIXEL_ENERGY),
+                    Some(MaskMarker::Remove) => self.energy[i] = MASK_REMOVE_BIAS,
+                    None => {},
+                }
+            }
+        }
     }
 
     #[inline(never)] // makes it easier to interpret callgrind output
@@ -90,20 +153,21 @@ impl Carver {
 
         unsafe {
         for i in 0..(num_pixels + 2) {
-            *self.dist_to.get_unchecked_mut(i) = i32::max_value();
+            *self.dist_to.get_unchecked_mut(i) = i64::max_value();
             *self.prev_vertex.get_unchecked_mut(i) = 0;
         }
 
         // fake source pixel edges to each pixel in the first row
         for pixel in 0..width {
-            *self.dist_to.get_unchecked_mut(pixel) = *self.energy.get_unchecked(pixel);
+            *self.dist_to.get_unchecked_mut(pixel) = *self.energy.get_unchecked(pixel) as i64;
             *self.prev_vertex.get_unchecked_mut(pixel) = fake_src;
         }
 
         {
             let mut relax_edge = |from_pixel: usize, to_pixel: usize| {
-                if *self.dist_to.get_unchecked(to_pixel) > *self.dist_to.get_unchecked(from_pixel) + *self.energy.get_unchecked(to_pixel) {
-                    *self.dist_to.get_unchecked_mut(to_pixel) = *self.dist_to.get_unchecked(from_pixel) + *self.energy.get_unchecked(to_pixel);
+                let new_dist = *self.dist_to.get_unchecked(from_pixel) + *self.energy.get_unchecked(to_pixel) as i64;
+                if *self.dist_to.get_unchecked(to_pixel) > new_dist {
+                    *self.dist_to.get_unchecked_mut(to_pixel) = new_dist;
                     *self.prev_vertex.get_unchecked_mut(to_pixel) = from_pixel;
                 }
             };
@@ -149,13 +213,62 @@ impl Carver {
         path.reverse();
         path
     }
+
+    /// Finds `k` minimum-energy seams one after another, marking each one's pixels with
+    /// `MAX_PIXEL_ENERGY` after finding it so the next search is steered away from it. The seams
+    /// are not guaranteed to be disjoint: border pixels already have `MAX_PIXEL_ENERGY` and interior
+    /// energies can exceed it, so a later seam can still pass through a marked pixel.
+    pub fn find_k_seams(&mut self, width: usize, height: usize, k: usize) -> Vec<Vec<usize>> {
+        let mut seams = Vec::with_capacity(k);
+        for _ in 0..k {
+            let seam = self.find_seam(width, height);
+            for &pixel in &seam {
+                self.energy[pixel] = MAX_PIXEL_ENERGY;
+            }
+            seams.push(seam);
+        }
+        seams
+    }
+}
+
+// AV1-style activity masking: scales gradient_energy by how textured the ACTIVITY_WINDOW square around (x, y) is.
+fn activity_mask(gradient_energy: i32, x: usize, y: usize, width: usize, height: usize, pixels: &[RGB<u8>]) -> i32 {
+    let half_window = (ACTIVITY_WINDOW / 2) as isize;
+    let x0 = (x as isize - half_window).max(0) as usize;
+    let x1 = ((x as isize + half_window) as usize).min(width);
+    let y0 = (y as isize - half_window).max(0) as usize;
+    let y1 = ((y as isize + half_window) as usize).min(height);
+    let count = ((x1 - x0) * (y1 - y0)) as f64;
+
+    let mut sum = 0i64;
+    for wy in y0..y1 {
+        for wx in x0..x1 {
+            sum += luma(pixels[wy * width + wx]) as i64;
+        }
+    }
+    let mean = sum as f64 / count;
+
+    let mut sq_diff_sum = 0f64;
+    for wy in y0..y1 {
+        for wx in x0..x1 {
+            let diff = luma(pixels[wy * width + wx]) as f64 - mean;
+            sq_diff_sum += diff * diff;
+        }
+    }
+    let svar = sq_diff_sum / count;
+
+    let scale = (svar + ACTIVITY_C2) / (ACTIVITY_C1 * ACTIVITY_C1 + svar * svar).sqrt();
+    (gradient_energy as f64 * scale).round() as i32
 }
 
+fn luma(pixel: RGB<u8>) -> i32 {
+    (77 * pixel.r as i32 + 150 * pixel.g as i32 + 29 * pixel.b as i32) >> 8
+}
 
 #[cfg(test)]
 mod tests {
     use lodepng::RGB;
-    use super::{Carver, MAX_PIXEL_ENERGY};
+    use super::{Carver, EnergyKind, MAX_PIXEL_ENERGY};
 
     fn rgb(r: u8, g: u8, b: u8) -> RGB<u8> {
         RGB { r: r, g: g, b: b }
@@ -169,7 +282,7 @@ mod tests {
             rgb(255, 153, 51), rgb(255, 153, 153), rgb(255, 153, 255),
             rgb(255, 203, 51), rgb(255, 204, 153), rgb(255, 205, 255),
             rgb(255, 255, 51), rgb(255, 255, 153), rgb(255, 255, 255),
-        )[..]);
+        )[..], EnergyKind::DualGradient, None);
 
         assert_eq!(carver.energy, vec!(
             MAX_PIXEL_ENERGY, MAX_PIXEL_ENERGY, MAX_PIXEL_ENERGY,
@@ -179,6 +292,61 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn perceptual_energy_suppresses_flat_regions_relative_to_dual_gradient() {
+        // ACTIVITY_WINDOW is 8x8, so the fixture needs to be bigger than that for a window to
+        // actually localize rather than degenerate into the whole image. A gentle horizontal
+        // luma gradient gives interior pixels a nonzero dual-gradient energy (there's a genuine
+        // difference between neighbouring columns) while keeping every 8x8 window's luma variance
+        // low, since the gradient only changes gradually across it.
+        let width = 10;
+        let height = 10;
+        let pixels: Vec<RGB<u8>> = (0..(width * height)).map(|i| {
+            let value = (100 + (i % width) * 3) as u8;
+            rgb(value, value, value)
+        }).collect();
+
+        let mut dual_gradient_carver = Carver::new(width * height);
+        dual_gradient_carver.calculate_energy(width, height, &pixels[..], EnergyKind::DualGradient, None);
+
+        let mut perceptual_carver = Carver::new(width * height);
+        perceptual_carver.calculate_energy(width, height, &pixels[..], EnergyKind::Perceptual, None);
+
+        // border pixels are always pinned at MAX_PIXEL_ENERGY regardless of energy function
+        assert_eq!(perceptual_carver.energy[0], MAX_PIXEL_ENERGY);
+
+        // an interior pixel sits in a low-variance neighbourhood despite the gradient giving it
+        // nonzero dual-gradient energy, so activity masking scales it down
+        let probe = 4 * width + 4;
+        assert!(perceptual_carver.energy[probe] > 0);
+        assert!(perceptual_carver.energy[probe] < dual_gradient_carver.energy[probe]);
+    }
+
+    #[test]
+    fn mask_protects_green_pixels_and_forces_removal_of_red_pixels() {
+        let pixels = vec!(
+            rgb(255, 101, 51), rgb(255, 101, 153), rgb(255, 101, 255),
+            rgb(255, 153, 51), rgb(255, 153, 153), rgb(255, 153, 255),
+            rgb(255, 203, 51), rgb(255, 204, 153), rgb(255, 205, 255),
+            rgb(255, 255, 51), rgb(255, 255, 153), rgb(255, 255, 255),
+        );
+        let mask = vec!(
+            rgb(0, 0, 0), rgb(0, 0, 0),   rgb(0, 0, 0),
+            rgb(0, 0, 0), rgb(0, 255, 0), rgb(0, 0, 0),
+            rgb(0, 0, 0), rgb(255, 0, 0), rgb(0, 0, 0),
+            rgb(0, 0, 0), rgb(0, 0, 0),   rgb(0, 0, 0),
+        );
+
+        let mut unmasked_carver = Carver::new(3 * 4);
+        unmasked_carver.calculate_energy(3, 4, &pixels[..], EnergyKind::DualGradient, None);
+
+        let mut masked_carver = Carver::new(3 * 4);
+        masked_carver.calculate_energy(3, 4, &pixels[..], EnergyKind::DualGradient, Some(&mask[..]));
+
+        assert_eq!(masked_carver.energy[4], unmasked_carver.energy[4] + MAX_PIXEL_ENERGY);
+        assert!(masked_carver.energy[7] < 0);
+    }
+
     #[test]
     fn finds_seam_as_given_in_example_in_spec() {
         let img_width = 6;
@@ -202,4 +370,30 @@ mod tests {
         // --  --  26  --  --  --
         assert_eq!(seam, vec!(2, 9, 15, 21, 26));
     }
+
+    #[test]
+    fn find_k_seams_finds_distinct_non_overlapping_seams() {
+        let img_width = 6;
+        let img_height = 5;
+        let mut carver = Carver::new(img_width * img_height);
+        carver.energy = vec!(
+            MAX_PIXEL_ENERGY, MAX_PIXEL_ENERGY, MAX_PIXEL_ENERGY, MAX_PIXEL_ENERGY, MAX_PIXEL_ENERGY, MAX_PIXEL_ENERGY,
+            MAX_PIXEL_ENERGY, 23346,            51304,            31519,            55112,            MAX_PIXEL_ENERGY,
+            MAX_PIXEL_ENERGY, 47908,            61346,            35919,            38887,            MAX_PIXEL_ENERGY,
+            MAX_PIXEL_ENERGY, 31400,            37927,            14437,            63076,            MAX_PIXEL_ENERGY,
+            MAX_PIXEL_ENERGY, MAX_PIXEL_ENERGY, MAX_PIXEL_ENERGY, MAX_PIXEL_ENERGY, MAX_PIXEL_ENERGY, MAX_PIXEL_ENERGY,
+        );
+
+        let seams = carver.find_k_seams(img_width, img_height, 3);
+
+        assert_eq!(seams.len(), 3);
+        for (i, seam_i) in seams.iter().enumerate() {
+            assert_eq!(seam_i.len(), img_height);
+            for seam_j in &seams[(i + 1)..] {
+                for pixel in seam_i {
+                    assert!(!seam_j.contains(pixel), "seams should never share a pixel");
+                }
+            }
+        }
+    }
 }
@@ -1,10 +1,13 @@
 extern crate getopts;
+extern crate gif;
 extern crate lodepng;
 extern crate time;
 
-use carving::Carver;
+use carving::{Carver, EnergyKind};
 use getopts::Options;
+use lodepng::{Bitmap, RGB};
 use std::env;
+use std::fs::File;
 use std::path::Path;
 use std::convert::AsMut;
 use std::process;
@@ -22,7 +25,12 @@ fn main() {
 
     let mut opts = Options::new();
     opts.optopt("o", "output", "path to output the resulting image", "OUTPUT-FILE");
-    opts.optopt("W", "width-reduction", "the number of pixels to reduce the width by", "WIDTH-COUNT");
+    opts.optopt("W", "width-reduction", "the number of pixels to reduce the width by; negative to enlarge instead", "WIDTH-COUNT");
+    opts.optopt("H", "height-reduction", "the number of pixels to reduce the height by", "HEIGHT-COUNT");
+    opts.optopt("e", "energy", "energy function to use: dual-gradient (default) or perceptual", "ENERGY-FN");
+    opts.optopt("m", "mask", "path to a same-sized mask image; green pixels protect, red pixels force removal", "MASK-FILE");
+    opts.optopt("g", "gif-output", "path to write an animated GIF visualizing each width-reduction seam removed", "GIF-FILE");
+    opts.optopt("c", "carve-fraction", "fraction (0.0-1.0) of the width reduction to carve via seams rather than resample; defaults to a heuristic based on the reduction ratio", "FRACTION");
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
@@ -39,8 +47,24 @@ fn main() {
         print_usage(&program, opts);
         process::exit(1);
     };
-    let width_reduction: u32 = matches.opt_str("W").unwrap_or("1".to_owned())
+    let width_delta: i32 = matches.opt_str("W").unwrap_or("1".to_owned())
         .parse().ok().expect("-W argument must be a number");
+    let height_reduction: u32 = matches.opt_str("H").unwrap_or("0".to_owned())
+        .parse().ok().expect("-H argument must be a number");
+    let energy_kind = match matches.opt_str("e").as_ref().map(String::as_str) {
+        None | Some("dual-gradient") => EnergyKind::DualGradient,
+        Some("perceptual") => EnergyKind::Perceptual,
+        Some(other) => {
+            println!("Unknown energy function: {}", other);
+            print_usage(&program, opts);
+            process::exit(1);
+        },
+    };
+    let carve_fraction: Option<f64> = matches.opt_str("c").map(|s| {
+        let fraction: f64 = s.parse().ok().expect("--carve-fraction argument must be a number");
+        assert!(fraction >= 0.0 && fraction <= 1.0, "--carve-fraction must be between 0.0 and 1.0");
+        fraction
+    });
 
     let mut bitmap = match lodepng::decode24_file(input_img_path) {
         Ok(bitmap) => bitmap,
@@ -50,17 +74,80 @@ fn main() {
     println!("Decoded {} x {} image at {}", bitmap.width, bitmap.height,
         input_img_path.to_str().expect("path should be valid"));
 
+    let mut mask = matches.opt_str("m").map(|mask_img_str| {
+        let mask_img_path = Path::new(&mask_img_str);
+        let mask_bitmap = match lodepng::decode24_file(mask_img_path) {
+            Ok(mask_bitmap) => mask_bitmap,
+            Err(reason) => panic!("Could not load mask {}, because: {}", mask_img_path.display(), reason),
+        };
+        assert_eq!((mask_bitmap.width, mask_bitmap.height), (bitmap.width, bitmap.height),
+            "mask image must have the same dimensions as the input image");
+
+        println!("Decoded {} x {} mask at {}", mask_bitmap.width, mask_bitmap.height, mask_img_str);
+        mask_bitmap.buffer
+    });
+
+    let mut gif_recorder = matches.opt_str("g").map(|gif_output_str| {
+        println!("Recording each width-reduction seam removed to {}", gif_output_str);
+        SeamGifRecorder::new(Path::new(&gif_output_str), bitmap.width, bitmap.height)
+    });
+
     let mut carver = Carver::new(bitmap.buffer.len());
-    println!("Reducing width of image by {} pixels... ", width_reduction);
     let start = time::precise_time_ns();
-    for _ in 0..width_reduction {
-        carver.calculate_energy(bitmap.width, bitmap.height,
-            subset_by_width_and_height(bitmap.buffer.as_mut(), bitmap.width, bitmap.height));
-        let seam = carver.find_seam(bitmap.width, bitmap.height);
 
-        lazy_remove_indexes_of(subset_by_width_and_height(bitmap.buffer.as_mut(), bitmap.width, bitmap.height), &seam);
-        bitmap.width = bitmap.width - 1;
+    if width_delta < 0 {
+        let enlarge_amount = (-width_delta) as u32;
+        if mask.is_some() {
+            println!("Note: --mask is not supported when enlarging width (negative -W); ignoring it for the enlarge step.");
+        }
+        if gif_recorder.is_some() {
+            println!("Note: --gif-output is not supported when enlarging width (negative -W); no frames will be recorded for the enlarge step.");
+        }
+        println!("Enlarging width of image by {} pixels...", enlarge_amount);
+        enlarge_width(&mut bitmap, &mut carver, enlarge_amount, energy_kind, &mut mask);
+
+        if height_reduction > 0 {
+            println!("Reducing height of image by {} pixels... ", height_reduction);
+            reduce_height(&mut bitmap, &mut carver, height_reduction, energy_kind, &mut mask);
+        }
+    } else {
+        let width_reduction = width_delta as u32;
+
+        if width_reduction > 0 && height_reduction > 0 {
+            // Recomputing energy over the full image dominates the cost of each seam removed, so
+            // the order matters. Estimate each ordering's cost from the width and height reduction
+            // ratios (the estimate video resizers use to pick a horizontal-vs-vertical scaling
+            // order) and run the cheaper one. horiz_first means removing horizontal seams, i.e.
+            // reducing height, first; with both ratios below 1 that wins only when 2 * hr - wr >= 1.
+            let wr = width_reduction as f64 / bitmap.width as f64;
+            let hr = height_reduction as f64 / bitmap.height as f64;
+            let horiz_first_cost = wr.max(1.0) * 2.0 + wr * hr.max(1.0);
+            let vert_first_cost = hr * wr.max(1.0) * 2.0 + hr.max(1.0);
+
+            if horiz_first_cost <= vert_first_cost {
+                println!("Reducing height by {} pixels, then width by {} pixels...", height_reduction, width_reduction);
+                reduce_height(&mut bitmap, &mut carver, height_reduction, energy_kind, &mut mask);
+                let width_before_carve = bitmap.width;
+                reduce_width_hybrid(&mut bitmap, &mut carver, width_reduction, energy_kind, &mut mask, &mut gif_recorder,
+                        carve_fraction.unwrap_or_else(|| default_carve_fraction(width_reduction, width_before_carve)));
+            } else {
+                println!("Reducing width by {} pixels, then height by {} pixels...", width_reduction, height_reduction);
+                let width_before_carve = bitmap.width;
+                reduce_width_hybrid(&mut bitmap, &mut carver, width_reduction, energy_kind, &mut mask, &mut gif_recorder,
+                        carve_fraction.unwrap_or_else(|| default_carve_fraction(width_reduction, width_before_carve)));
+                reduce_height(&mut bitmap, &mut carver, height_reduction, energy_kind, &mut mask);
+            }
+        } else if width_reduction > 0 {
+            println!("Reducing width of image by {} pixels... ", width_reduction);
+            let width_before_carve = bitmap.width;
+            reduce_width_hybrid(&mut bitmap, &mut carver, width_reduction, energy_kind, &mut mask, &mut gif_recorder,
+                        carve_fraction.unwrap_or_else(|| default_carve_fraction(width_reduction, width_before_carve)));
+        } else if height_reduction > 0 {
+            println!("Reducing height of image by {} pixels... ", height_reduction);
+            reduce_height(&mut bitmap, &mut carver, height_reduction, energy_kind, &mut mask);
+        }
     }
+
     let finish = time::precise_time_ns();
     println!("Finished in {} ms", (finish / 1000000) as i64 - (start / 1000000) as i64);
 
@@ -81,10 +168,301 @@ fn main() {
     };
 }
 
+/// Removes `amount` vertical seams (each spanning the full height) to shrink the image's width.
+/// `mask`, if given, is carved in lockstep with the image so it stays aligned with it.
+fn reduce_width(bitmap: &mut Bitmap<RGB<u8>>, carver: &mut Carver, amount: u32, energy_kind: EnergyKind,
+                 mask: &mut Option<Vec<RGB<u8>>>, gif_recorder: &mut Option<SeamGifRecorder>) {
+    for _ in 0..amount {
+        {
+            let mask_slice = mask.as_ref().map(|m| &m[..(bitmap.width * bitmap.height)]);
+            carver.calculate_energy(bitmap.width, bitmap.height,
+                subset_by_width_and_height(bitmap.buffer.as_mut(), bitmap.width, bitmap.height), energy_kind, mask_slice);
+        }
+        let seam = carver.find_seam(bitmap.width, bitmap.height);
+
+        if let Some(ref mut recorder) = *gif_recorder {
+            recorder.record_seam(subset_by_width_and_height(bitmap.buffer.as_mut(), bitmap.width, bitmap.height),
+                bitmap.width, bitmap.height, &seam);
+        }
+
+        lazy_remove_indexes_of(subset_by_width_and_height(bitmap.buffer.as_mut(), bitmap.width, bitmap.height), &seam);
+        if let Some(ref mut mask_buf) = *mask {
+            lazy_remove_indexes_of(subset_by_width_and_height(mask_buf.as_mut(), bitmap.width, bitmap.height), &seam);
+        }
+        bitmap.width -= 1;
+    }
+}
+
+// removing a horizontal seam is the same problem as removing a vertical seam from the transposed image, so we
+// transpose, reuse the vertical-seam machinery, then transpose back.
+fn reduce_height(bitmap: &mut Bitmap<RGB<u8>>, carver: &mut Carver, amount: u32, energy_kind: EnergyKind,
+                  mask: &mut Option<Vec<RGB<u8>>>) {
+    for _ in 0..amount {
+        let width = bitmap.width;
+        let height = bitmap.height;
+
+        let mut transposed = vec![RGB { r: 0, g: 0, b: 0 }; width * height];
+        transpose(subset_by_width_and_height(bitmap.buffer.as_mut(), width, height), width, height, &mut transposed);
+
+        let mut transposed_mask = mask.as_mut().map(|mask_buf| {
+            let mut t = vec![RGB { r: 0, g: 0, b: 0 }; width * height];
+            transpose(subset_by_width_and_height(mask_buf.as_mut(), width, height), width, height, &mut t);
+            t
+        });
+
+        carver.calculate_energy(height, width, &transposed, energy_kind,
+            transposed_mask.as_ref().map(|t| &t[..]));
+        let seam = carver.find_seam(height, width);
+        lazy_remove_indexes_of(&mut transposed, &seam);
+        if let Some(ref mut t) = transposed_mask {
+            lazy_remove_indexes_of(t, &seam);
+        }
+
+        transpose(&transposed[..(height - 1) * width], height - 1, width,
+            subset_by_width_and_height(bitmap.buffer.as_mut(), width, height - 1));
+        if let Some(ref t) = transposed_mask {
+            if let Some(ref mut mask_buf) = *mask {
+                transpose(&t[..(height - 1) * width], height - 1, width,
+                    subset_by_width_and_height(mask_buf.as_mut(), width, height - 1));
+            }
+        }
+        bitmap.height -= 1;
+    }
+}
+
+/// Enlarges the image's width by `amount` pixels by duplicating the `amount` lowest-energy seams (see `find_k_seams`).
+fn enlarge_width(bitmap: &mut Bitmap<RGB<u8>>, carver: &mut Carver, amount: u32, energy_kind: EnergyKind,
+                 mask: &mut Option<Vec<RGB<u8>>>) {
+    if amount == 0 {
+        return;
+    }
+
+    let width = bitmap.width;
+    let height = bitmap.height;
+
+    // find_k_seams only steers each seam away from the earlier ones; once amount reaches width,
+    // some row has no unused pixel left, so seams are bound to share one there and insertion
+    // puts the same interpolated pixel after it more than once
+    assert!((amount as usize) < width,
+        "-W enlarge amount ({}) must be less than the image width ({})", amount, width);
+
+    carver.calculate_energy(width, height,
+        subset_by_width_and_height(bitmap.buffer.as_mut(), width, height), energy_kind, None);
+    let seams = carver.find_k_seams(width, height, amount as usize);
+
+    let mut to_insert: Vec<usize> = seams.into_iter().flat_map(|seam| seam.into_iter()).collect();
+    to_insert.sort();
+
+    let mut enlarged = vec![RGB { r: 0, g: 0, b: 0 }; (width + amount as usize) * height];
+    lazy_insert_indexes_of(subset_by_width_and_height(bitmap.buffer.as_mut(), width, height), &to_insert, &mut enlarged);
+
+    // the mask plays no part in picking the seams, but it is widened along the same seams so it
+    // stays aligned with the enlarged image for any later reduction
+    if let Some(ref mut mask_buf) = *mask {
+        let mut enlarged_mask = vec![RGB { r: 0, g: 0, b: 0 }; (width + amount as usize) * height];
+        lazy_insert_indexes_of(subset_by_width_and_height(mask_buf.as_mut(), width, height), &to_insert, &mut enlarged_mask);
+        *mask_buf = enlarged_mask;
+    }
+
+    bitmap.buffer = enlarged;
+    bitmap.width = width + amount as usize;
+
+    // the carver was sized for the original (smaller) pixel count; grow it so later calls
+    // (e.g. a subsequent reduce_height) don't panic against the now-larger image
+    carver.ensure_capacity(bitmap.buffer.len());
+}
+
+/// Reduces width by `amount` pixels, carving `carve_fraction` of them and Lanczos-resampling the rest.
+fn reduce_width_hybrid(bitmap: &mut Bitmap<RGB<u8>>, carver: &mut Carver, amount: u32, energy_kind: EnergyKind,
+                        mask: &mut Option<Vec<RGB<u8>>>, gif_recorder: &mut Option<SeamGifRecorder>,
+                        carve_fraction: f64) {
+    let carve_amount = ((amount as f64) * carve_fraction).round() as u32;
+    let carve_amount = carve_amount.min(amount);
+    let resample_amount = amount - carve_amount;
+
+    if carve_amount > 0 {
+        reduce_width(bitmap, carver, carve_amount, energy_kind, mask, gif_recorder);
+    }
+
+    if resample_amount > 0 {
+        let src_width = bitmap.width;
+        let height = bitmap.height;
+        let dst_width = src_width - resample_amount as usize;
+        let taps = resample_taps(src_width, dst_width);
+
+        let mut resampled = vec![RGB { r: 0, g: 0, b: 0 }; dst_width * height];
+        resample_width(subset_by_width_and_height(bitmap.buffer.as_mut(), src_width, height), src_width, height,
+            &taps, &mut resampled);
+        bitmap.buffer = resampled;
+        bitmap.width = dst_width;
+
+        if let Some(ref mut mask_buf) = *mask {
+            let mut resampled_mask = vec![RGB { r: 0, g: 0, b: 0 }; dst_width * height];
+            resample_width(subset_by_width_and_height(mask_buf.as_mut(), src_width, height), src_width, height,
+                &taps, &mut resampled_mask);
+            *mask_buf = resampled_mask;
+        }
+    }
+}
+
+/// Heuristic carve fraction for when `--carve-fraction` isn't given: small reductions are carved
+/// entirely, but the carved share shrinks towards a floor as the reduction ratio grows.
+fn default_carve_fraction(width_reduction: u32, width: usize) -> f64 {
+    const FULL_CARVE_RATIO: f64 = 0.2;
+    const MIN_CARVE_FRACTION: f64 = 0.2;
+
+    let reduction_ratio = width_reduction as f64 / width as f64;
+    if reduction_ratio <= FULL_CARVE_RATIO {
+        1.0
+    } else {
+        (FULL_CARVE_RATIO / reduction_ratio).max(MIN_CARVE_FRACTION)
+    }
+}
+
+/// `columns[dst_x]` gives the inclusive `[start, end]` source column range and normalized weights for `dst_x`.
+struct ResampleTaps {
+    columns: Vec<(usize, usize, Vec<f64>)>,
+}
+
+const LANCZOS_RADIUS: f64 = 3.0;
+
+/// The Lanczos-3 windowed sinc kernel: `sinc(x) * sinc(x / a)` for `|x| < a`, zero beyond it.
+fn lanczos(x: f64) -> f64 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    if x.abs() >= LANCZOS_RADIUS {
+        return 0.0;
+    }
+    let px = std::f64::consts::PI * x;
+    LANCZOS_RADIUS * px.sin() * (px / LANCZOS_RADIUS).sin() / (px * px)
+}
+
+/// Precomputes the filter taps to resample a `src_width`-wide row down to `dst_width`.
+fn resample_taps(src_width: usize, dst_width: usize) -> ResampleTaps {
+    let scale = src_width as f64 / dst_width as f64;
+    let filter_scale = scale.max(1.0);
+    let radius = LANCZOS_RADIUS * filter_scale;
+
+    let columns = (0..dst_width).map(|dst_x| {
+        let center = (dst_x as f64 + 0.5) * scale - 0.5;
+        let start = (center - radius).floor().max(0.0) as usize;
+        let end = ((center + radius).ceil() as usize).min(src_width - 1);
+
+        let mut weights: Vec<f64> = (start..(end + 1))
+            .map(|src_x| lanczos((src_x as f64 - center) / filter_scale))
+            .collect();
+        let weight_sum: f64 = weights.iter().sum();
+        if weight_sum != 0.0 {
+            for weight in weights.iter_mut() {
+                *weight /= weight_sum;
+            }
+        }
+
+        (start, end, weights)
+    }).collect();
+
+    ResampleTaps { columns: columns }
+}
+
+/// Applies `taps` to shrink `src` (`src_width` x `height`) horizontally into `dst`, which must have room
+/// for `taps.columns.len() * height` pixels.
+fn resample_width(src: &[RGB<u8>], src_width: usize, height: usize, taps: &ResampleTaps, dst: &mut [RGB<u8>]) {
+    let dst_width = taps.columns.len();
+
+    for y in 0..height {
+        let row = &src[(y * src_width)..((y + 1) * src_width)];
+        for (dst_x, &(start, end, ref weights)) in taps.columns.iter().enumerate() {
+            let mut r = 0.0;
+            let mut g = 0.0;
+            let mut b = 0.0;
+
+            for (i, src_x) in (start..(end + 1)).enumerate() {
+                let weight = weights[i];
+                r += row[src_x].r as f64 * weight;
+                g += row[src_x].g as f64 * weight;
+                b += row[src_x].b as f64 * weight;
+            }
+
+            dst[y * dst_width + dst_x] = RGB {
+                r: r.round().max(0.0).min(255.0) as u8,
+                g: g.round().max(0.0).min(255.0) as u8,
+                b: b.round().max(0.0).min(255.0) as u8,
+            };
+        }
+    }
+}
+
+/// Records the width-reduction carving process as an animated GIF, one frame per seam removed,
+/// with the about-to-be-removed seam painted bright red over the current frame.
+struct SeamGifRecorder {
+    encoder: gif::Encoder<File>,
+    canvas_width: usize,
+    canvas_height: usize,
+}
+
+const SEAM_FRAME_DELAY_HUNDREDTHS: u16 = 4;
+const SEAM_OVERLAY_ALPHA: i32 = 200;
+
+impl SeamGifRecorder {
+    fn new(path: &Path, width: usize, height: usize) -> SeamGifRecorder {
+        let file = File::create(path).unwrap();
+        let encoder = gif::Encoder::new(file, width as u16, height as u16, &[]).unwrap();
+        SeamGifRecorder { encoder: encoder, canvas_width: width, canvas_height: height }
+    }
+
+    /// Paints `seam` bright red over `pixels` and appends the result as the next GIF frame.
+    fn record_seam(&mut self, pixels: &[RGB<u8>], width: usize, height: usize, seam: &[usize]) {
+        let mut rgb = Vec::with_capacity(pixels.len() * 3);
+        for pixel in pixels {
+            rgb.push(pixel.r);
+            rgb.push(pixel.g);
+            rgb.push(pixel.b);
+        }
+
+        for &i in seam {
+            rgb[i * 3] = blend(rgb[i * 3], 255, SEAM_OVERLAY_ALPHA);
+            rgb[i * 3 + 1] = blend(rgb[i * 3 + 1], 0, SEAM_OVERLAY_ALPHA);
+            rgb[i * 3 + 2] = blend(rgb[i * 3 + 2], 0, SEAM_OVERLAY_ALPHA);
+        }
+
+        // canvas stays at its original (largest) size for the whole animation, so pad a narrower frame's
+        // carved-away columns with a flat background colour instead of leaving a ghost strip behind.
+        let mut canvas = vec![0u8; self.canvas_width * self.canvas_height * 3];
+        for y in 0..height {
+            let src_row = &rgb[(y * width * 3)..((y + 1) * width * 3)];
+            let dst_start = y * self.canvas_width * 3;
+            canvas[dst_start..(dst_start + width * 3)].copy_from_slice(src_row);
+        }
+
+        let mut frame = gif::Frame::from_rgb(self.canvas_width as u16, self.canvas_height as u16, &canvas[..]);
+        frame.delay = SEAM_FRAME_DELAY_HUNDREDTHS;
+        self.encoder.write_frame(&frame).unwrap();
+    }
+}
+
+/// Alpha-blends `new` over `prev` with `alpha` out of 256, matching the bitmap blend helpers
+/// used by plotting backends: `prev += (new-prev)*a/256`.
+fn blend(prev: u8, new: u8, alpha: i32) -> u8 {
+    let prev = prev as i32;
+    let new = new as i32;
+    (prev + (new - prev) * alpha / 256) as u8
+}
+
 fn subset_by_width_and_height<A>(slice: &mut [A], width: usize, height: usize) -> &mut [A] {
     &mut slice[..(width * height)]
 }
 
+/// Transposes a `src_width` x `src_height` image of pixels into `dst`, which must have room for
+/// `src_width * src_height` pixels, laid out as `src_height` x `src_width`.
+fn transpose(src: &[RGB<u8>], src_width: usize, src_height: usize, dst: &mut [RGB<u8>]) {
+    for y in 0..src_height {
+        for x in 0..src_width {
+            dst[x * src_height + y] = src[y * src_width + x];
+        }
+    }
+}
+
 /// For each index `A` of `to_remove` into `slice`, set `slice[A] = slice[A + 1]`. The last `to_remove.len()` items in
 /// `slice` will contain junk after this. Runs in linear time and requires `to_remove` to be sorted w.r.t. `slice`.
 fn lazy_remove_indexes_of<A: Clone>(slice: &mut [A], to_remove: &Vec<usize>) {
@@ -103,9 +481,37 @@ fn lazy_remove_indexes_of<A: Clone>(slice: &mut [A], to_remove: &Vec<usize>) {
     }
 }
 
+fn average_pixel(a: RGB<u8>, b: RGB<u8>) -> RGB<u8> {
+    RGB {
+        r: ((a.r as u16 + b.r as u16) / 2) as u8,
+        g: ((a.g as u16 + b.g as u16) / 2) as u8,
+        b: ((a.b as u16 + b.b as u16) / 2) as u8,
+    }
+}
+
+/// The insertion-side counterpart to `lazy_remove_indexes_of`: duplicates `src[index]` right after itself in `dst`
+/// for each index in `to_insert` (sorted w.r.t. `src`), averaging the duplicate with its right-hand neighbour.
+fn lazy_insert_indexes_of(src: &[RGB<u8>], to_insert: &Vec<usize>, dst: &mut [RGB<u8>]) {
+    let mut prev_end = 0;
+    for (offset, &idx) in to_insert.iter().enumerate() {
+        let dst_start = prev_end + offset;
+        let run_len = idx + 1 - prev_end;
+        dst[dst_start..(dst_start + run_len)].clone_from_slice(&src[prev_end..(idx + 1)]);
+
+        let neighbor = if idx + 1 < src.len() { src[idx + 1] } else { src[idx] };
+        dst[dst_start + run_len] = average_pixel(src[idx], neighbor);
+
+        prev_end = idx + 1;
+    }
+
+    let dst_start = prev_end + to_insert.len();
+    dst[dst_start..].clone_from_slice(&src[prev_end..]);
+}
+
 #[cfg(test)]
 mod tests {
-    use super::lazy_remove_indexes_of;
+    use super::{lazy_remove_indexes_of, lazy_insert_indexes_of, average_pixel};
+    use lodepng::RGB;
 
     #[test]
     fn lazy_remove_indexes_of_works_correctly() {
@@ -134,4 +540,33 @@ mod tests {
         //   1 2 3
         assert_eq!(vec, vec!(1, 2, 3));
     }
+
+    fn rgb(r: u8, g: u8, b: u8) -> RGB<u8> {
+        RGB { r: r, g: g, b: b }
+    }
+
+    fn as_tuple(pixel: RGB<u8>) -> (u8, u8, u8) {
+        (pixel.r, pixel.g, pixel.b)
+    }
+
+    #[test]
+    fn average_pixel_rounds_down_towards_the_first_pixel() {
+        assert_eq!(as_tuple(average_pixel(rgb(0, 0, 0), rgb(10, 11, 255))), (5, 5, 127));
+    }
+
+    #[test]
+    fn lazy_insert_indexes_of_duplicates_marked_pixels_and_shifts_the_rest_right() {
+        let src: Vec<RGB<u8>> = (0..8).map(|i| rgb(i, i, i)).collect();
+        let to_insert = vec!(1, 4);
+        let mut dst = vec!(rgb(0, 0, 0); src.len() + to_insert.len());
+
+        lazy_insert_indexes_of(&src, &to_insert, &mut dst);
+
+        let expected = vec!(
+            (0, 0, 0), (1, 1, 1), as_tuple(average_pixel(rgb(1, 1, 1), rgb(2, 2, 2))),
+            (2, 2, 2), (3, 3, 3), (4, 4, 4), as_tuple(average_pixel(rgb(4, 4, 4), rgb(5, 5, 5))),
+            (5, 5, 5), (6, 6, 6), (7, 7, 7),
+        );
+        assert_eq!(dst.into_iter().map(as_tuple).collect::<Vec<_>>(), expected);
+    }
 }